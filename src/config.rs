@@ -0,0 +1,434 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! TOML configuration for stream-matching rules and LED colours.
+//!
+//! The inline ignore list and the `media.category == "Manager"` heuristic were
+//! never going to keep up with every distro and audio setup, so both are now
+//! data. An ordered list of [`Rule`]s is matched (regex, per field) against a
+//! node's `media.class`/`media.category`/`application.name`/`node.name` and
+//! the first match decides whether we act on it; a [`Palette`] maps every
+//! `State`/`Event` pair to the bytes that drive the puck LED.
+//!
+//! The whole thing lives behind a process-global [`RwLock`] so the `SIGHUP`
+//! reload path can swap it out without restarting. With no config file present
+//! the built-in defaults reproduce the historical behaviour exactly.
+
+use crate::{Event, State};
+use log::*;
+use regex::Regex;
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::sync::{OnceLock, RwLock};
+
+/// What to do with a node that matches a rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Include,
+    Ignore,
+}
+
+/// A single ordered match rule as written in the config file. Every specified
+/// field is a regular expression; an omitted field matches anything, so a rule
+/// with no fields is a catch-all. Patterns are compiled into a [`Rule`] at load
+/// time so the node-enumeration hot path never touches the regex compiler.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RawRule {
+    #[serde(default)]
+    pub media_class: Option<String>,
+    #[serde(default)]
+    pub media_category: Option<String>,
+    #[serde(default)]
+    pub application_name: Option<String>,
+    #[serde(default)]
+    pub node_name: Option<String>,
+    pub action: Action,
+}
+
+impl RawRule {
+    /// Compile this rule's patterns once. Returns `Err` (after logging which
+    /// pattern was bad) if any field fails to compile, so a user typo surfaces
+    /// a diagnostic at load time instead of silently never matching.
+    fn compile(self) -> Result<Rule, ()> {
+        Ok(Rule {
+            media_class: compile_field(self.media_class)?,
+            media_category: compile_field(self.media_category)?,
+            application_name: compile_field(self.application_name)?,
+            node_name: compile_field(self.node_name)?,
+            action: self.action,
+        })
+    }
+}
+
+fn compile_field(pattern: Option<String>) -> Result<Option<Regex>, ()> {
+    match pattern {
+        None => Ok(None),
+        Some(pattern) => match Regex::new(&pattern) {
+            Ok(re) => Ok(Some(re)),
+            Err(e) => {
+                warn!("Ignoring rule with invalid pattern {:?}: {}", pattern, e);
+                Err(())
+            }
+        },
+    }
+}
+
+/// A match rule with its patterns already compiled.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub media_class: Option<Regex>,
+    pub media_category: Option<Regex>,
+    pub application_name: Option<Regex>,
+    pub node_name: Option<Regex>,
+    pub action: Action,
+}
+
+impl Rule {
+    fn matches(&self, class: &str, category: &str, app: &str, node: &str) -> bool {
+        field_matches(&self.media_class, class)
+            && field_matches(&self.media_category, category)
+            && field_matches(&self.application_name, app)
+            && field_matches(&self.node_name, node)
+    }
+}
+
+fn field_matches(pattern: &Option<Regex>, value: &str) -> bool {
+    match pattern {
+        None => true,
+        Some(re) => re.is_match(value),
+    }
+}
+
+/// Which streams the button and inbound commands act on.
+///
+/// `All` preserves the historical global-mute behaviour; the other modes leave
+/// untargeted applications untouched. Selected in the `[target]` table of the
+/// config file and re-read on `SIGHUP`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum Target {
+    /// Every tracked stream.
+    #[default]
+    All,
+    /// Only streams that are currently capturing audio.
+    ActiveCapture,
+    /// Only streams whose name is listed in `apps`. A stream's name is its
+    /// `application.name`, or its `node.name` when it has no `application.name`.
+    AllowList {
+        #[serde(default)]
+        apps: Vec<String>,
+    },
+}
+
+/// A single LED colour channel.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Colour {
+    Red,
+    Green,
+    Blue,
+}
+
+impl Colour {
+    fn bit(self) -> u8 {
+        match self {
+            Colour::Red => 0x01,
+            Colour::Green => 0x02,
+            Colour::Blue => 0x04,
+        }
+    }
+}
+
+/// The LED intensity / pulse mode.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Intensity {
+    Strong,
+    Weak,
+    FastPulse,
+    SlowPulse,
+}
+
+impl Intensity {
+    fn bit(self) -> u8 {
+        match self {
+            Intensity::Strong => 0x00,
+            Intensity::Weak => 0x10,
+            Intensity::FastPulse => 0x20,
+            Intensity::SlowPulse => 0x30,
+        }
+    }
+}
+
+/// The colour/intensity of the LED for one state/event pair.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Led {
+    #[serde(default)]
+    pub colours: Vec<Colour>,
+    #[serde(default = "strong")]
+    pub intensity: Intensity,
+}
+
+fn strong() -> Intensity {
+    Intensity::Strong
+}
+
+impl Led {
+    fn new(colours: &[Colour], intensity: Intensity) -> Self {
+        Self {
+            colours: colours.to_vec(),
+            intensity,
+        }
+    }
+
+    fn byte(&self) -> u8 {
+        self.colours
+            .iter()
+            .fold(0u8, |acc, colour| acc | colour.bit())
+            | self.intensity.bit()
+    }
+}
+
+/// The two LED appearances of a state: while the button is pressed, and
+/// otherwise.
+#[derive(Debug, Clone, Deserialize)]
+pub struct StateLeds {
+    pub press: Led,
+    pub idle: Led,
+}
+
+impl StateLeds {
+    fn new(press: Led, idle: Led) -> Self {
+        Self { press, idle }
+    }
+}
+
+/// The full LED palette, one [`StateLeds`] per [`State`].
+#[derive(Debug, Clone, Deserialize)]
+pub struct Palette {
+    #[serde(default = "default_silent")]
+    pub silent: StateLeds,
+    #[serde(default = "default_muted")]
+    pub muted: StateLeds,
+    #[serde(default = "default_unmuted")]
+    pub unmuted: StateLeds,
+    #[serde(default = "default_confused")]
+    pub confused: StateLeds,
+}
+
+fn default_silent() -> StateLeds {
+    StateLeds::new(
+        Led::new(&[Colour::Blue], Intensity::Weak),
+        Led::new(&[], Intensity::Strong),
+    )
+}
+
+fn default_muted() -> StateLeds {
+    StateLeds::new(
+        Led::new(&[Colour::Red], Intensity::Strong),
+        Led::new(&[Colour::Red], Intensity::SlowPulse),
+    )
+}
+
+fn default_unmuted() -> StateLeds {
+    StateLeds::new(
+        Led::new(&[Colour::Green], Intensity::Strong),
+        Led::new(&[Colour::Green], Intensity::Weak),
+    )
+}
+
+fn default_confused() -> StateLeds {
+    let both = Led::new(&[Colour::Red, Colour::Green], Intensity::FastPulse);
+    StateLeds::new(both.clone(), both)
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            silent: default_silent(),
+            muted: default_muted(),
+            unmuted: default_unmuted(),
+            confused: default_confused(),
+        }
+    }
+}
+
+impl Palette {
+    fn byte(&self, state: State, event: Event) -> u8 {
+        let leds = match state {
+            State::Silent => &self.silent,
+            State::Muted => &self.muted,
+            State::Unmuted => &self.unmuted,
+            State::Confused => &self.confused,
+        };
+
+        if let Event::Press = event {
+            leds.press.byte()
+        } else {
+            leds.idle.byte()
+        }
+    }
+}
+
+/// The configuration as written in the TOML file, before pattern compilation.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RawConfig {
+    #[serde(default = "default_rules")]
+    pub rules: Vec<RawRule>,
+    #[serde(default)]
+    pub palette: Palette,
+    #[serde(default)]
+    pub target: Target,
+    #[serde(default)]
+    pub restore_unmuted_on_exit: bool,
+}
+
+impl RawConfig {
+    /// Compile every rule's patterns once, dropping (with a diagnostic) any
+    /// rule whose regexes do not compile.
+    fn compile(self) -> Config {
+        Config {
+            rules: self
+                .rules
+                .into_iter()
+                .filter_map(|rule| rule.compile().ok())
+                .collect(),
+            palette: self.palette,
+            target: self.target,
+            restore_unmuted_on_exit: self.restore_unmuted_on_exit,
+        }
+    }
+}
+
+/// The rules that reproduce the historical ignore heuristic: skip the
+/// well-known manager/mixer nodes, act on everything else.
+fn default_rules() -> Vec<RawRule> {
+    // Turn `rule` into one that ignores the nodes its patterns match.
+    fn ignore(mut rule: RawRule) -> RawRule {
+        rule.action = Action::Ignore;
+        rule
+    }
+    fn blank() -> RawRule {
+        RawRule {
+            media_class: None,
+            media_category: None,
+            application_name: None,
+            node_name: None,
+            action: Action::Include,
+        }
+    }
+
+    vec![
+        ignore(RawRule {
+            media_category: Some("^Manager$".to_string()),
+            ..blank()
+        }),
+        ignore(RawRule {
+            application_name: Some("^GNOME Settings$".to_string()),
+            ..blank()
+        }),
+        ignore(RawRule {
+            application_name: Some("^PulseAudio Volume Control$".to_string()),
+            ..blank()
+        }),
+        // Catch-all: anything not matched above is acted on.
+        blank(),
+    ]
+}
+
+impl Default for RawConfig {
+    fn default() -> Self {
+        Self {
+            rules: default_rules(),
+            palette: Palette::default(),
+            target: Target::default(),
+            restore_unmuted_on_exit: false,
+        }
+    }
+}
+
+/// The parsed configuration with all patterns compiled.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub rules: Vec<Rule>,
+    pub palette: Palette,
+    pub target: Target,
+    /// Whether a clean shutdown should unmute every stream. Off by default: a
+    /// mute tool must not silently reopen a deliberately-muted mic when the
+    /// daemon is restarted or killed.
+    pub restore_unmuted_on_exit: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        RawConfig::default().compile()
+    }
+}
+
+impl Config {
+    fn resolve(&self, class: &str, category: &str, app: &str, node: &str) -> Action {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(class, category, app, node))
+            .map(|rule| rule.action)
+            .unwrap_or(Action::Include)
+    }
+}
+
+/// The path of the config file, `$XDG_CONFIG_HOME/mutemagic/config.toml` with
+/// the usual `~/.config` fall back.
+pub fn config_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join("mutemagic").join("config.toml"))
+}
+
+fn store() -> &'static RwLock<Config> {
+    static STORE: OnceLock<RwLock<Config>> = OnceLock::new();
+    STORE.get_or_init(|| RwLock::new(Config::default()))
+}
+
+fn load_from_file() -> Option<Config> {
+    let path = config_path()?;
+    let text = std::fs::read_to_string(&path).ok()?;
+    match toml::from_str::<RawConfig>(&text) {
+        Ok(config) => {
+            info!("Loaded configuration from {:?}", path);
+            Some(config.compile())
+        }
+        Err(e) => {
+            warn!("Ignoring invalid configuration {:?}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// (Re)read the configuration file, falling back to the built-in defaults when
+/// it is absent or invalid. Called at start-up and from the `SIGHUP` handler.
+pub fn reload() {
+    let config = load_from_file().unwrap_or_default();
+    *store().write().unwrap() = config;
+}
+
+/// Resolve whether a node should be acted on, consulting the ordered rules.
+pub fn resolve(class: &str, category: &str, app: &str, node: &str) -> Action {
+    store().read().unwrap().resolve(class, category, app, node)
+}
+
+/// The LED feedback byte for a state/event pair, from the configured palette.
+pub fn feedback_byte(state: State, event: Event) -> u8 {
+    store().read().unwrap().palette.byte(state, event)
+}
+
+/// The configured target mode, re-read on every button press or command so a
+/// `SIGHUP` reload takes effect without restarting.
+pub fn target() -> Target {
+    store().read().unwrap().target.clone()
+}
+
+/// Whether a clean shutdown should restore every stream to unmuted. Defaults
+/// to off so killing the daemon never silently reopens a muted mic.
+pub fn restore_unmuted_on_exit() -> bool {
+    store().read().unwrap().restore_unmuted_on_exit
+}
@@ -0,0 +1,78 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Abstraction over the family of MuteMe-style HID pucks.
+//!
+//! Every supported button differs in three places: the USB vendor/product it
+//! enumerates as, the layout of its input reports, and the bytes it expects
+//! to drive its LED. [`MuteDevice`] captures exactly those three, and the
+//! [`registry`] lets the rest of the crate stay device-agnostic — the reader
+//! thread opens the first registered device that is present and the main loop
+//! talks to it purely through this trait.
+
+use crate::{Event, State};
+use hidapi::HidApi;
+
+/// A physical mute button whose USB identity, report layout and LED protocol
+/// are known to the crate.
+pub trait MuteDevice: Send + Sync {
+    /// The `(vendor, product)` pairs this implementation drives.
+    fn usb_ids(&self) -> &[(u16, u16)];
+
+    /// Decode a raw input report into a button [`Event`], returning `None`
+    /// for reports that carry nothing we act on.
+    fn decode_report(&self, buf: &[u8]) -> Option<Event>;
+
+    /// Encode the LED feedback bytes for the given state/event pair.
+    fn encode_feedback(&self, state: State, event: Event) -> Vec<u8>;
+}
+
+/// The original MuteMe / MuteMe Mini puck: a single button on `buf[3]` and a
+/// one-byte RGB+intensity LED command padded out to three bytes.
+pub struct MuteMe;
+
+impl MuteDevice for MuteMe {
+    fn usb_ids(&self) -> &[(u16, u16)] {
+        const IDS: &[(u16, u16)] = &[(0x20a0, 0x42da)];
+        IDS
+    }
+
+    fn decode_report(&self, buf: &[u8]) -> Option<Event> {
+        match buf.get(3)? {
+            4 => Some(Event::Press),
+            0 => Some(Event::Release),
+            _ => None,
+        }
+    }
+
+    fn encode_feedback(&self, state: State, event: Event) -> Vec<u8> {
+        vec![state.msg(&event), 0, 0]
+    }
+}
+
+/// Every known device implementation, consulted in order.
+pub fn registry() -> &'static [&'static dyn MuteDevice] {
+    static REGISTRY: &[&dyn MuteDevice] = &[&MuteMe];
+    REGISTRY
+}
+
+/// The union of every registered device's `(vendor, product)` pairs, used to
+/// tell the hotplug monitor which devices are interesting.
+pub fn all_usb_ids() -> Vec<(u16, u16)> {
+    registry()
+        .iter()
+        .flat_map(|dev| dev.usb_ids().iter().copied())
+        .collect()
+}
+
+/// Open the first registered device that is currently present, returning both
+/// the HID handle and the implementation that claimed it.
+pub fn open_first(api: &HidApi) -> Option<(hidapi::HidDevice, &'static dyn MuteDevice)> {
+    for dev in registry() {
+        for &(vid, pid) in dev.usb_ids() {
+            if let Ok(handle) = api.open(vid, pid) {
+                return Some((handle, *dev));
+            }
+        }
+    }
+    None
+}
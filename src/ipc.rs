@@ -0,0 +1,206 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! A local control socket so status bars, scripts and desktop widgets can
+//! drive and observe the mute state without touching the physical puck.
+//!
+//! The protocol is deliberately boring: each message is a little-endian `u32`
+//! byte length followed by a bincode-serialized enum. Clients send
+//! [`Command`]s and receive [`Event`]s. A client that only ever reads is a
+//! pure subscriber; one that writes [`Command::Query`] will get the current
+//! state pushed back on the same connection like any other state change.
+
+use crate::{Message, State};
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Upper bound on an inbound frame body. A control message is a tiny enum, so
+/// anything larger is a bug or a malicious client trying to force a giant
+/// allocation out of a 4-byte header.
+const MAX_FRAME_LEN: usize = 64 * 1024;
+
+/// How long a broadcast write may block before we give up on a subscriber.
+///
+/// Broadcasting happens on the PipeWire main-loop thread; a client that stops
+/// reading must never be able to stall mute feedback, so a stuck write times
+/// out and the subscriber is dropped. The timeout only affects writes, so the
+/// cloned reader handle keeps blocking normally.
+const WRITE_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// A request from a client, carried inbound over the control socket.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Command {
+    Mute,
+    Unmute,
+    Toggle,
+    Query,
+}
+
+/// A notification pushed outbound to every connected subscriber.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Event {
+    StateChanged(State),
+    Applications(Vec<AppStatus>),
+}
+
+/// The tracked state of a single capture application, as reported to clients.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppStatus {
+    pub name: String,
+    pub muted: bool,
+    pub capturing: bool,
+}
+
+/// The path of the control socket, taken from `$XDG_RUNTIME_DIR` with a
+/// fall back to the system temporary directory for non-systemd logins.
+pub fn socket_path() -> PathBuf {
+    let dir = std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir);
+    dir.join("mutemagic.sock")
+}
+
+/// The set of connected clients that want to be told about state changes.
+///
+/// Cheap to clone (it is just a shared handle) so a copy can live in the
+/// PipeWire main loop alongside the copy held by the accept thread.
+#[derive(Clone, Default)]
+pub struct Subscribers {
+    inner: Arc<Mutex<Vec<UnixStream>>>,
+}
+
+impl Subscribers {
+    fn add(&self, stream: UnixStream) {
+        self.inner.lock().unwrap().push(stream);
+    }
+
+    /// Push `event` to every subscriber, dropping any whose socket has gone
+    /// away (the client disconnected or stopped reading).
+    pub fn broadcast(&self, event: Event) {
+        let mut subs = self.inner.lock().unwrap();
+        subs.retain_mut(|stream| write_frame(stream, &event).is_ok());
+    }
+}
+
+/// A writable handle to the client that issued a command, so [`Command::Query`]
+/// can answer on the originating connection only instead of broadcasting the
+/// reply to every subscriber.
+pub struct Responder(UnixStream);
+
+impl Responder {
+    /// Push a single event back to the querying client, ignoring a write error
+    /// (the client has gone away, which the read loop will notice too).
+    pub fn send(&mut self, event: &Event) {
+        let _ = write_frame(&mut self.0, event);
+    }
+}
+
+fn write_frame<W: Write>(w: &mut W, event: &Event) -> io::Result<()> {
+    let bytes = bincode::serialize(event).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    w.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    w.write_all(&bytes)?;
+    w.flush()
+}
+
+fn read_frame<R: Read>(r: &mut R) -> io::Result<Command> {
+    let mut len = [0u8; 4];
+    r.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame length {} exceeds maximum {}", len, MAX_FRAME_LEN),
+        ));
+    }
+    let mut bytes = vec![0u8; len];
+    r.read_exact(&mut bytes)?;
+    bincode::deserialize(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Bind the control socket and spawn the accept loop on its own thread.
+///
+/// Inbound commands are forwarded to the PipeWire main loop as
+/// [`Message::Command`]; the returned [`Subscribers`] handle is used by that
+/// loop to broadcast every [`Message::State`] back out.
+pub fn serve(tx: pipewire::channel::Sender<Message>) -> io::Result<Subscribers> {
+    let path = socket_path();
+
+    // A stale socket from a previous run would make bind() fail with EADDRINUSE.
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)?;
+    info!("Listening on control socket: {:?}", path);
+
+    let subscribers = Subscribers::default();
+    {
+        let subscribers = subscribers.clone();
+        thread::spawn(move || accept_loop(listener, tx, subscribers));
+    }
+
+    Ok(subscribers)
+}
+
+fn accept_loop(listener: UnixListener, tx: pipewire::channel::Sender<Message>, subs: Subscribers) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Control socket accept failed: {}", e);
+                continue;
+            }
+        };
+
+        // One handle stays with us to push events; the clone drives the
+        // per-client read loop that turns frames into commands.
+        let reader = match stream.try_clone() {
+            Ok(reader) => reader,
+            Err(e) => {
+                warn!("Cannot clone client socket: {}", e);
+                continue;
+            }
+        };
+
+        // Bound how long a broadcast write to this client may block so a
+        // subscriber that stops reading cannot wedge the main loop.
+        if let Err(e) = stream.set_write_timeout(Some(WRITE_TIMEOUT)) {
+            warn!("Cannot set client write timeout: {}", e);
+            continue;
+        }
+
+        subs.add(stream);
+        let tx = tx.clone();
+        thread::spawn(move || client_loop(reader, tx));
+    }
+}
+
+fn client_loop(mut stream: UnixStream, tx: pipewire::channel::Sender<Message>) {
+    loop {
+        match read_frame(&mut stream) {
+            Ok(command) => {
+                debug!("Control command: {:?}", command);
+                // Hand the main loop a private handle to this client so a
+                // Query is answered here and not broadcast to everyone else.
+                let responder = match stream.try_clone() {
+                    Ok(reply) => Responder(reply),
+                    Err(e) => {
+                        debug!("Cannot clone client socket for reply: {}", e);
+                        break;
+                    }
+                };
+                if tx.send(Message::Command(command, responder)).is_err() {
+                    break;
+                }
+            }
+            Err(e) => {
+                if e.kind() != io::ErrorKind::UnexpectedEof {
+                    debug!("Control client read error: {}", e);
+                }
+                break;
+            }
+        }
+    }
+}
@@ -1,12 +1,21 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+mod config;
+mod device;
+mod ipc;
+
+use device::MuteDevice;
 use hidapi::HidApi;
+use ipc::Command;
 use libspa::pod::deserialize::PodDeserializer;
 use libspa::pod::serialize::PodSerializer;
 use libspa::pod::{Object, Property, PropertyFlags, Value};
 use libspa_sys::{SPA_PARAM_Props, SPA_PROP_mute, SPA_TYPE_OBJECT_Props};
 use log::*;
 use pipewire::{prelude::*, Context, MainLoop};
+use serde::{Deserialize, Serialize};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
 use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::fmt;
@@ -15,14 +24,6 @@ use std::os::fd::AsRawFd;
 use std::rc::Rc;
 use std::thread;
 
-const RED: u8 = 0x01;
-const GREEN: u8 = 0x02;
-const BLUE: u8 = 0x04;
-const STRONG: u8 = 0x00;
-const WEAK: u8 = 0x10;
-const FAST_PULSE: u8 = 0x20;
-const SLOW_PULSE: u8 = 0x30;
-
 #[derive(Debug, Clone, Copy)]
 enum Event {
     Press,
@@ -30,7 +31,7 @@ enum Event {
     Hotplug,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 enum State {
     Silent,
     Muted,
@@ -50,15 +51,7 @@ impl State {
     }
 
     fn msg(&self, event: &Event) -> u8 {
-        match (self, event) {
-            (State::Silent, Event::Press) => BLUE | WEAK,
-            (State::Silent, _) => 0,
-            (State::Muted, Event::Press) => RED | STRONG,
-            (State::Muted, _) => RED | SLOW_PULSE,
-            (State::Unmuted, Event::Press) => GREEN | STRONG,
-            (State::Unmuted, _) => GREEN | WEAK,
-            (State::Confused, _) => RED | GREEN | FAST_PULSE,
-        }
+        config::feedback_byte(*self, *event)
     }
 
     fn is_muted(&self) -> Option<bool> {
@@ -70,20 +63,36 @@ impl State {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug)]
 enum Message {
     State(State),
     Event(Event),
+    /// An inbound control command, carrying a handle for replying to the
+    /// issuing client (used by `Query`).
+    Command(Command, ipc::Responder),
+    Reload,
+    Shutdown,
 }
 
 struct StreamContext {
     mute: bool,
+    /// Whether the node is actively moving audio, derived from its node state.
+    /// This lets the button target only the applications really using the mic.
+    capturing: bool,
+    /// Display name, the `application.name` where known and falling back to
+    /// `node.name`; also the key the allow-list and IPC view match against.
+    name: String,
     node: pipewire::node::Node,
     _node_listener: pipewire::node::NodeListener,
 }
 
 impl StreamContext {
-    fn new(node: pipewire::node::Node, update_mute: impl Fn(bool) + 'static) -> Self {
+    fn new(
+        node: pipewire::node::Node,
+        name: String,
+        update_mute: impl Fn(bool) + 'static,
+        update_capturing: impl Fn(bool) + 'static,
+    ) -> Self {
         debug!("Listening to node: {:?}", &node);
 
         // This listener is not used after creation (and has no useful methods)
@@ -91,6 +100,10 @@ impl StreamContext {
         // stream context to ensure it is not dropped.
         let _node_listener = node
             .add_listener_local()
+            .info(move |info| {
+                // A node counts as "capturing" once the graph has it running.
+                update_capturing(info.state() == pipewire::node::NodeState::Running);
+            })
             .param(move |_s, _pt, _u1, _u2, buf| {
                 let (_, data) = PodDeserializer::deserialize_from::<Value>(&buf).unwrap();
 
@@ -116,16 +129,53 @@ impl StreamContext {
 
         Self {
             mute: false,
+            capturing: false,
+            name,
             node,
             _node_listener,
         }
     }
 }
 
+/// Which streams a button press or command acts on.
+///
+/// The default is [`TargetMode::All`], matching the historical all-or-nothing
+/// behaviour; the other modes leave untargeted applications untouched.
+enum TargetMode {
+    /// Every tracked stream, as the tool has always behaved.
+    All,
+    /// Only streams that are currently capturing audio.
+    ActiveCapture,
+    /// Only streams whose name appears in the allow-list.
+    AllowList(Vec<String>),
+}
+
+impl TargetMode {
+    fn targets(&self, ctx: &StreamContext) -> bool {
+        match self {
+            TargetMode::All => true,
+            TargetMode::ActiveCapture => ctx.capturing,
+            TargetMode::AllowList(names) => names.iter().any(|name| name == &ctx.name),
+        }
+    }
+
+    /// The target mode selected in the configuration, re-read each time so a
+    /// `SIGHUP` reload of `[target]` takes effect without a restart.
+    fn from_config() -> Self {
+        match config::target() {
+            config::Target::All => TargetMode::All,
+            config::Target::ActiveCapture => TargetMode::ActiveCapture,
+            config::Target::AllowList { apps } => TargetMode::AllowList(apps),
+        }
+    }
+}
+
 impl fmt::Debug for StreamContext {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("Point")
+        f.debug_struct("StreamContext")
+            .field("name", &self.name)
             .field("mute", &self.mute)
+            .field("capturing", &self.capturing)
             .field("node", &self.node)
             .finish()
     }
@@ -133,15 +183,29 @@ impl fmt::Debug for StreamContext {
 
 struct HotplugMonitor {
     socket: udev::MonitorSocket,
+    usb_ids: Vec<(u16, u16)>,
 }
 
 impl HotplugMonitor {
-    fn new(subsys: &str) -> io::Result<Self> {
+    fn new(subsys: &str, usb_ids: Vec<(u16, u16)>) -> io::Result<Self> {
         let socket = udev::MonitorBuilder::new()?
             .match_subsystem(subsys)?
             .listen()?;
 
-        Ok(Self { socket })
+        Ok(Self { socket, usb_ids })
+    }
+
+    /// Whether a hotplug event belongs to one of the registered devices. The
+    /// hidraw node itself carries no VID/PID, so we climb to the parent USB
+    /// device and match against the union of registered ids.
+    fn matches(&self, event: &udev::Event) -> bool {
+        if self.usb_ids.is_empty() {
+            return true;
+        }
+
+        device_usb_id(&event.device())
+            .map(|id| self.usb_ids.contains(&id))
+            .unwrap_or(false)
     }
 
     fn wait_for_event(&self) -> io::Result<udev::Event> {
@@ -166,9 +230,123 @@ impl HotplugMonitor {
     }
 }
 
+/// Read the `(vendor, product)` of the USB device backing a hidraw node.
+fn device_usb_id(device: &udev::Device) -> Option<(u16, u16)> {
+    let usb = device
+        .parent_with_subsystem_devtype("usb", "usb_device")
+        .ok()??;
+    let vid = u16::from_str_radix(usb.attribute_value("idVendor")?.to_str()?, 16).ok()?;
+    let pid = u16::from_str_radix(usb.attribute_value("idProduct")?.to_str()?, 16).ok()?;
+    Some((vid, pid))
+}
+
+/// Push `next_mute_state` out to the streams selected by `mode`.
+///
+/// This is the release-path logic shared between a physical button release and
+/// an inbound [`Command`]: `Muted`/`Unmuted` serialize a `SPA_PROP_mute` pod
+/// and `set_param` it on every targeted node, while `Silent` means there is
+/// nothing to mute.
+fn set_mute(nodes: &RefCell<HashMap<u32, StreamContext>>, next_mute_state: State, mode: &TargetMode) {
+    if let Some(mute) = next_mute_state.is_muted() {
+        let pod = Value::Object(Object {
+            type_: SPA_TYPE_OBJECT_Props,
+            id: SPA_PARAM_Props,
+            properties: vec![Property {
+                key: SPA_PROP_mute,
+                flags: PropertyFlags::empty(),
+                value: Value::Bool(mute),
+            }],
+        });
+
+        let mut data = std::io::Cursor::new(Vec::<u8>::new());
+        let res = PodSerializer::serialize(&mut data, &pod);
+
+        if let Ok((data, len)) = res {
+            let data = data.get_ref();
+            assert!(data.len() == len as usize);
+
+            let nodes = nodes.borrow_mut();
+
+            let mut any = false;
+            for (_k, ctx) in nodes.iter().filter(|(_, ctx)| mode.targets(ctx)) {
+                ctx.node.set_param(libspa::param::ParamType::Props, 0, data);
+                any = true;
+            }
+
+            if !any {
+                info!("No mute change event (no target streams)");
+            }
+        } else {
+            unreachable!();
+        }
+    } else if State::Silent == next_mute_state {
+        info!("No mute change event (no streams)");
+    }
+}
+
+/// The mute rollup over just the streams `mode` targets.
+///
+/// The toggle direction must be derived from the targeted subset, not the
+/// global rollup: in `ActiveCapture`/`AllowList` modes the targeted and
+/// untargeted apps differ, so the global state is `Confused` precisely when
+/// the feature is in use, which would otherwise pin the button to mute-only.
+fn targeted_state(nodes: &RefCell<HashMap<u32, StreamContext>>, mode: &TargetMode) -> State {
+    State::from_bools(
+        nodes
+            .borrow()
+            .values()
+            .filter(|ctx| mode.targets(ctx))
+            .map(|ctx| ctx.mute),
+    )
+}
+
+/// A lazily-opened, long-lived write handle to the puck plus the device
+/// implementation that drives its LED. `None` means we have no usable handle
+/// yet (or the last one errored); the next hotplug reopens it.
+type Puck = RefCell<Option<(hidapi::HidDevice, &'static dyn MuteDevice)>>;
+
+/// (Re)open the write handle against the first present registered device.
+///
+/// Called on `Event::Hotplug` and on a configuration reload rather than once
+/// per message. The hidapi context itself is created once; this only refreshes
+/// its device list and opens a new handle.
+fn reopen_puck(api: &HidApi, puck: &Puck) {
+    let _ = api.refresh_devices();
+    *puck.borrow_mut() = device::open_first(api);
+}
+
+/// Write the LED feedback for `state`/`event` through the persistent handle,
+/// invalidating it on error so the next hotplug reopens a fresh one.
+fn feedback(puck: &Puck, state: State, event: Event) {
+    let mut guard = puck.borrow_mut();
+    if let Some((handle, dev)) = guard.as_ref() {
+        if handle.write(&dev.encode_feedback(state, event)).is_err() {
+            *guard = None;
+        }
+    }
+}
+
+/// Snapshot the tracked applications for the IPC layer, reporting which are
+/// live and which are muted.
+fn app_snapshot(nodes: &RefCell<HashMap<u32, StreamContext>>) -> Vec<ipc::AppStatus> {
+    nodes
+        .borrow()
+        .values()
+        .map(|ctx| ipc::AppStatus {
+            name: ctx.name.clone(),
+            muted: ctx.mute,
+            capturing: ctx.capturing,
+        })
+        .collect()
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     pretty_env_logger::init();
 
+    // Read the configuration (match rules and LED palette) up front; SIGHUP
+    // re-reads it later without restarting.
+    config::reload();
+
     let orig_hook = std::panic::take_hook();
     std::panic::set_hook(Box::new(move |panic_info| {
         // invoke the default handler, then exit the process
@@ -183,13 +361,18 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         let tx = tx.clone();
 
         thread::spawn(move || {
-            let monitor = HotplugMonitor::new("hidraw").expect("Cannot monitor hotplug events");
+            let monitor = HotplugMonitor::new("hidraw", device::all_usb_ids())
+                .expect("Cannot monitor hotplug events");
+
+            // One hidapi context for the lifetime of the thread; we merely
+            // refresh its device list across reconnects rather than rebuilding
+            // the whole context each time round the loop.
+            let api = HidApi::new().expect("Failed to create API instance");
 
             loop {
-                let api = HidApi::new().expect("Failed to create API instance");
-                let puck = api.open(0x20a0, 0x42da);
+                let _ = api.refresh_devices();
 
-                if let Ok(puck) = puck {
+                if let Some((puck, dev)) = device::open_first(&api) {
                     info!("Connected to mute device");
                     monitor.clear_events();
                     let _ = tx.send(Message::Event(Event::Hotplug));
@@ -201,14 +384,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                             break;
                         }
 
-                        match buf[3] {
-                            4 => {
-                                let _ = tx.send(Message::Event(Event::Press));
-                            }
-                            0 => {
-                                let _ = tx.send(Message::Event(Event::Release));
-                            }
-                            _ => (),
+                        if let Some(event) = dev.decode_report(&buf) {
+                            let _ = tx.send(Message::Event(event));
                         }
                     }
                 } else {
@@ -216,7 +393,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     while {
                         let event = monitor.wait_for_event();
                         if let Ok(event) = event {
-                            event.event_type() != udev::EventType::Add
+                            event.event_type() != udev::EventType::Add || !monitor.matches(&event)
                         } else {
                             info!("Re-waiting for event");
                             true
@@ -232,6 +409,31 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     // puck so we can use the main thread to run the pipewire main loop.
     //
 
+    // Open the control socket so headless clients can drive and observe the
+    // mute state. Commands arrive back over the same `tx` as button events.
+    let subscribers = ipc::serve(tx.clone())?;
+
+    // Route signals through the main loop rather than acting on them in the
+    // handler context: SIGINT/SIGTERM ask for a clean shutdown, SIGHUP for a
+    // live configuration reload. This keeps the daemon well behaved under
+    // systemd and never leaves the puck LEDs half-written.
+    {
+        let tx = tx.clone();
+        let mut signals = Signals::new([SIGINT, SIGTERM, SIGHUP])?;
+
+        thread::spawn(move || {
+            for signal in signals.forever() {
+                let msg = match signal {
+                    SIGHUP => Message::Reload,
+                    _ => Message::Shutdown,
+                };
+                if tx.send(msg).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
     let mainloop = MainLoop::new()?;
     let context = Context::new(&mainloop)?;
     let core = context.connect(None)?;
@@ -256,43 +458,80 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     let media_class = properties.get("media.class").unwrap_or("");
                     let media_category = properties.get("media.category").unwrap_or("");
                     let application_name = properties.get("application.name").unwrap_or("UNKNOWN");
+                    let node_name = properties.get("node.name").unwrap_or(application_name);
 
                     // Programs like GNOME Settings and PulseAudio Volume Control create nodes
-                    // where media.class is "Stream/Input/Audio".
-                    //
-                    // In newer distros we can recognise these by looking at the media.category field.
-                    // Sadly in older distros this does not work and we simply rely on an ignore
-                    // list. Ideas a better heuristic would be very welcome...
-                    const IGNORE_LIST: &[&str] = &["GNOME Settings", "PulseAudio Volume Control"];
-                    let ignore =
-                        media_category == "Manager" || IGNORE_LIST.contains(&application_name);
+                    // where media.class is "Stream/Input/Audio". Rather than a hardcoded
+                    // ignore list we consult the configured match rules, so users can adapt
+                    // to new distros and audio setups without recompiling.
+                    let ignore = config::resolve(
+                        media_class,
+                        media_category,
+                        application_name,
+                        node_name,
+                    ) == config::Action::Ignore;
 
                     if media_class == "Stream/Input/Audio" && !ignore {
                         if let Ok(node) = registry.bind::<pipewire::node::Node, _>(global) {
-                            let ctx = StreamContext::new(node, {
-                                let global_id = global.id;
-                                let nodes = nodes.clone();
-                                let tx = tx.clone();
-
-                                move |mute| {
-                                    info!(
-                                        "Mute change event: global id {} is {}",
-                                        global_id,
-                                        if mute { "muted" } else { "unmuted" }
-                                    );
-
-                                    let mut nodes = nodes.borrow_mut();
-                                    let mut node = nodes.get_mut(&global_id);
-                                    if let Some(node) = &mut node {
-                                        node.mute = mute;
-
-                                        let state = State::from_bools(
-                                            nodes.iter().map(|(_, ctx)| ctx.mute),
+                            // Prefer the human-friendly application name, but
+                            // keep node.name for the nameless streams so the
+                            // allow-list and IPC view always have a key.
+                            let name = if application_name == "UNKNOWN" {
+                                node_name.to_string()
+                            } else {
+                                application_name.to_string()
+                            };
+
+                            let ctx = StreamContext::new(
+                                node,
+                                name,
+                                {
+                                    let global_id = global.id;
+                                    let nodes = nodes.clone();
+                                    let tx = tx.clone();
+
+                                    move |mute| {
+                                        info!(
+                                            "Mute change event: global id {} is {}",
+                                            global_id,
+                                            if mute { "muted" } else { "unmuted" }
                                         );
-                                        let _ = tx.send(Message::State(state));
+
+                                        let mut nodes = nodes.borrow_mut();
+                                        if let Some(node) = nodes.get_mut(&global_id) {
+                                            node.mute = mute;
+
+                                            let state = State::from_bools(
+                                                nodes.iter().map(|(_, ctx)| ctx.mute),
+                                            );
+                                            let _ = tx.send(Message::State(state));
+                                        }
+                                    }
+                                },
+                                {
+                                    let global_id = global.id;
+                                    let nodes = nodes.clone();
+                                    let tx = tx.clone();
+
+                                    move |capturing| {
+                                        let mut nodes = nodes.borrow_mut();
+                                        if let Some(node) = nodes.get_mut(&global_id) {
+                                            if node.capturing == capturing {
+                                                return;
+                                            }
+                                            node.capturing = capturing;
+
+                                            // Capture activity does not change
+                                            // the global mute rollup, but the
+                                            // IPC view needs refreshing.
+                                            let state = State::from_bools(
+                                                nodes.iter().map(|(_, ctx)| ctx.mute),
+                                            );
+                                            let _ = tx.send(Message::State(state));
+                                        }
                                     }
-                                }
-                            });
+                                },
+                            );
 
                             nodes.borrow_mut().insert(global.id, ctx);
                             info!("Added global: {:?} ({})", global.id, application_name);
@@ -319,79 +558,105 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         })
         .register();
 
+    // A single hidapi context and write handle owned by the main loop. The
+    // handle is opened on hotplug and reused for every LED update, so we no
+    // longer rebuild hidapi just to write three bytes.
+    let hidapi = HidApi::new()?;
+    let puck: Puck = RefCell::new(None);
+
     let _rx = rx.attach(&mainloop, {
         let mute_state = Cell::new(State::Silent);
         let button_state = Cell::new(Event::Release);
+        let subscribers = subscribers.clone();
+        let mainloop = mainloop.downgrade();
 
         move |msg| {
             match msg {
                 Message::State(state) => {
                     mute_state.set(state);
+
+                    // Mirror every state change out to the control socket so
+                    // subscribers see the same Silent/Muted/Unmuted/Confused
+                    // transitions as the puck LEDs, plus the per-application
+                    // view of which streams are live and muted.
+                    subscribers.broadcast(ipc::Event::StateChanged(state));
+                    subscribers.broadcast(ipc::Event::Applications(app_snapshot(&nodes)));
                 }
                 Message::Event(evt) => {
                     button_state.set(evt);
 
+                    // A hotplug means a (possibly different) puck just arrived:
+                    // open a fresh persistent write handle for it.
+                    if let Event::Hotplug = evt {
+                        reopen_puck(&hidapi, &puck);
+                    }
+
                     // On button release we update the current mute state and,
                     // if needed, ask the pipewire thread to update the streams
                     if let Event::Release = button_state.get() {
-                        let next_mute_state = match mute_state.get() {
+                        let mode = TargetMode::from_config();
+                        let next_mute_state = match targeted_state(&nodes, &mode) {
                             State::Silent => State::Silent,
                             State::Muted => State::Unmuted,
                             _ => State::Muted,
                         };
 
-                        if let Some(mute) = next_mute_state.is_muted() {
-                            let pod = Value::Object(Object {
-                                type_: SPA_TYPE_OBJECT_Props,
-                                id: SPA_PARAM_Props,
-                                properties: vec![Property {
-                                    key: SPA_PROP_mute,
-                                    flags: PropertyFlags::empty(),
-                                    value: Value::Bool(mute),
-                                }],
-                            });
-
-                            let mut data = std::io::Cursor::new(Vec::<u8>::new());
-                            let res = PodSerializer::serialize(&mut data, &pod);
-
-                            if let Ok((data, len)) = res {
-                                let data = data.get_ref();
-                                assert!(data.len() == len as usize);
-
-                                let nodes = nodes.borrow_mut();
-
-                                for (_k, ctx) in nodes.iter() {
-                                    ctx.node.set_param(libspa::param::ParamType::Props, 0, data);
-                                }
-                            } else {
-                                unreachable!();
-                            }
-                        } else if State::Silent == next_mute_state {
-                            info!("No mute change event (no streams)");
+                        set_mute(&nodes, next_mute_state, &mode);
+                    }
+                }
+                Message::Command(command, mut responder) => {
+                    // Commands take the same release path as the physical
+                    // button, just with the target state chosen explicitly.
+                    let mode = TargetMode::from_config();
+                    let next_mute_state = match command {
+                        Command::Mute => State::Muted,
+                        Command::Unmute => State::Unmuted,
+                        Command::Toggle => match targeted_state(&nodes, &mode) {
+                            State::Silent => State::Silent,
+                            State::Muted => State::Unmuted,
+                            _ => State::Muted,
+                        },
+                        // Query changes nothing; reply on the querying
+                        // connection only, not to every subscriber.
+                        Command::Query => {
+                            responder.send(&ipc::Event::StateChanged(mute_state.get()));
+                            responder.send(&ipc::Event::Applications(app_snapshot(&nodes)));
+                            return;
                         }
+                    };
+
+                    set_mute(&nodes, next_mute_state, &mode);
+                }
+                Message::Reload => {
+                    // SIGHUP: re-read configuration (match rules, LED palette)
+                    // and reopen the puck in case its device changed, all
+                    // without restarting the process.
+                    info!("Reloading configuration");
+                    config::reload();
+                    reopen_puck(&hidapi, &puck);
+                }
+                Message::Shutdown => {
+                    // SIGINT/SIGTERM: clear the LEDs and stop the main loop
+                    // with a clean exit. The mic is left as-is unless the user
+                    // opted in, so a restart or kill never silently reopens a
+                    // deliberately-muted mic.
+                    info!("Shutting down");
+                    if config::restore_unmuted_on_exit() {
+                        set_mute(&nodes, State::Unmuted, &TargetMode::All);
+                    }
+                    feedback(&puck, State::Silent, Event::Release);
+
+                    if let Some(mainloop) = mainloop.upgrade() {
+                        mainloop.quit();
                     }
+                    return;
                 }
             }
 
-            let api = HidApi::new().expect("Failed to create API instance");
-            let puck = api.open(0x20a0, 0x42da);
-            if let Ok(puck) = puck {
-                let _ = puck.write(&[mute_state.get().msg(&button_state.get()), 0, 0]);
-            }
+            feedback(&puck, mute_state.get(), button_state.get());
         }
     });
 
-    ctrlc::set_handler(move || {
-        // Try to clear the LEDs before we exit...
-        let api = HidApi::new().expect("Failed to create API instance");
-        let puck = api.open(0x20a0, 0x42da);
-        if let Ok(puck) = puck {
-            let _ = puck.write(&[0, 0, 0]);
-        }
-        std::process::exit(1);
-    })
-    .unwrap();
-
     mainloop.run();
 
     Ok(())